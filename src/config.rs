@@ -0,0 +1,281 @@
+use std::{
+    collections::HashMap, env::VarError, fmt::Display, net::SocketAddr,
+    str::FromStr, time::Duration,
+};
+
+use crate::servers::{is_on_domain, server_host};
+
+/// Every setting of this server, that can be changed through the environment
+#[derive(Debug)]
+pub struct Config {
+    /// The address to listen on. Set via `BIND_ADDR`
+    pub bind_addr: SocketAddr,
+    /// The maximum amount of time a single scrapbook advice request may take,
+    /// before we give up and tell the client to retry later. Set in seconds
+    /// via `SCRAPBOOK_ADVICE_TIMEOUT`.
+    ///
+    /// Just like `read_timeout`, this only bounds how long the client waits.
+    /// The query keeps running, so this must stay well above the time a
+    /// healthy advice query takes on the largest servers
+    pub advice_timeout: Duration,
+    /// The maximum amount of time the other read only endpoints may take. Set
+    /// in seconds via `READ_TIMEOUT`.
+    ///
+    /// This is a deadline on the whole request, not just on acquiring a
+    /// connection, because the pool is owned by sf-info-lib. A timed out query
+    /// keeps running and holding its connection, so this must stay well above
+    /// the time a healthy query takes on a busy server. Otherwise clients
+    /// retry slow, but working queries and only add to the load
+    pub read_timeout: Duration,
+    /// The game domains, that we accept reports and crawl requests for. Any
+    /// subdomain of these is allowed as well. Set as a comma separated list
+    /// via `ALLOWED_GAME_DOMAINS`. If unset, every server is allowed
+    pub allowed_game_domains: Option<Vec<String>>,
+    /// The maximum amount of players and HoF pages, that get handed out to
+    /// crawlers per minute for a single game server. Set via
+    /// `CRAWL_HANDOUT_LIMIT`. If unset, handouts are not limited
+    pub handout_limit: Option<u32>,
+    /// Overrides `handout_limit` for specific servers. Set as a comma separated
+    /// list of `host=limit` pairs via `CRAWL_HANDOUT_LIMITS`
+    pub handout_limits: HashMap<String, u32>,
+}
+
+impl Config {
+    /// Reads the config from the environment. Unset values fall back to their
+    /// defaults, but values, that are set and can not be used, are an error
+    pub fn from_env() -> Result<Config, String> {
+        Config::from_vars(|name| std::env::var(name))
+    }
+
+    /// Reads the config from the variables `var` returns for each name
+    fn from_vars(
+        var: impl Fn(&str) -> Result<String, VarError>,
+    ) -> Result<Config, String> {
+        let env = Env(var);
+        Ok(Config {
+            bind_addr: env
+                .or("BIND_ADDR", SocketAddr::from(([127, 0, 0, 1], 4949)))?,
+            advice_timeout: env.secs("SCRAPBOOK_ADVICE_TIMEOUT", 30)?,
+            read_timeout: env.secs("READ_TIMEOUT", 30)?,
+            allowed_game_domains: env.domains("ALLOWED_GAME_DOMAINS")?,
+            handout_limit: env.opt("CRAWL_HANDOUT_LIMIT")?,
+            handout_limits: env.limits("CRAWL_HANDOUT_LIMITS")?,
+        })
+    }
+
+    /// The amount of players and HoF pages, that may be handed out for the
+    /// server at `host` per minute, if there is a limit
+    pub fn handout_limit(&self, host: &str) -> Option<u32> {
+        self.handout_limits
+            .get(host)
+            .copied()
+            .or(self.handout_limit)
+    }
+
+    /// Checks if the game server at `url` is on one of the allowed domains
+    pub fn allows_server(&self, url: &str) -> bool {
+        let Some(domains) = &self.allowed_game_domains else {
+            return true;
+        };
+        let host = server_host(url);
+        domains.iter().any(|domain| is_on_domain(&host, domain))
+    }
+
+    /// Reads the config from a fixed set of variables
+    #[cfg(test)]
+    pub fn from_pairs(vars: &[(&str, &str)]) -> Result<Config, String> {
+        let vars: std::collections::HashMap<_, _> =
+            vars.iter().copied().collect();
+        Config::from_vars(|name| {
+            vars.get(name)
+                .map(|a| a.to_string())
+                .ok_or(VarError::NotPresent)
+        })
+    }
+}
+
+/// Parses config values from a lookup of environment variables
+struct Env<F>(F);
+
+impl<F: Fn(&str) -> Result<String, VarError>> Env<F> {
+    fn opt<T>(&self, name: &str) -> Result<Option<T>, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        match (self.0)(name) {
+            Ok(val) => val.parse().map(Some).map_err(|e| {
+                format!("{name} is set to {val:?}, which is invalid: {e}")
+            }),
+            Err(VarError::NotPresent) => Ok(None),
+            Err(e) => Err(format!("{name} can not be read: {e}")),
+        }
+    }
+
+    fn or<T>(&self, name: &str, default: T) -> Result<T, String>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        Ok(self.opt(name)?.unwrap_or(default))
+    }
+
+    fn secs(&self, name: &str, default: u64) -> Result<Duration, String> {
+        match self.or(name, default)? {
+            0 => Err(format!("{name} must be at least 1 second")),
+            secs => Ok(Duration::from_secs(secs)),
+        }
+    }
+
+    fn domains(&self, name: &str) -> Result<Option<Vec<String>>, String> {
+        let Some(val) = self.opt::<String>(name)? else {
+            return Ok(None);
+        };
+        let domains: Vec<_> = val
+            .split(',')
+            .map(server_host)
+            .map(|a| a.trim_start_matches('.').to_string())
+            .filter(|a| !a.is_empty())
+            .collect();
+        if domains.is_empty() {
+            // Accepting no server at all is almost certainly a mistake
+            return Err(format!("{name} must contain at least one domain"));
+        }
+        Ok(Some(domains))
+    }
+
+    fn limits(&self, name: &str) -> Result<HashMap<String, u32>, String> {
+        let Some(val) = self.opt::<String>(name)? else {
+            return Ok(HashMap::new());
+        };
+        val.split(',')
+            .filter(|a| !a.trim().is_empty())
+            .map(|entry| {
+                let (host, limit) = entry.split_once('=').ok_or_else(|| {
+                    format!(
+                        "{name} contains {entry:?}, which is not host=limit"
+                    )
+                })?;
+                let limit = limit.trim().parse::<u32>().map_err(|e| {
+                    format!("{name} contains {entry:?}, which is invalid: {e}")
+                })?;
+                Ok::<_, String>((server_host(host), limit))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let config = Config::from_pairs(&[]).unwrap();
+        assert_eq!(config.bind_addr, SocketAddr::from(([127, 0, 0, 1], 4949)));
+        assert_eq!(config.advice_timeout, Duration::from_secs(30));
+        assert_eq!(config.read_timeout, Duration::from_secs(30));
+        assert_eq!(config.allowed_game_domains, None);
+        assert!(config.allows_server("https://anything.example.com"));
+        assert_eq!(config.handout_limit("s1.sfgame.net"), None);
+    }
+
+    #[test]
+    fn overrides() {
+        let config = Config::from_pairs(&[
+            ("BIND_ADDR", "0.0.0.0:80"),
+            ("SCRAPBOOK_ADVICE_TIMEOUT", "12"),
+            ("READ_TIMEOUT", "1"),
+        ])
+        .unwrap();
+        assert_eq!(config.bind_addr, SocketAddr::from(([0, 0, 0, 0], 80)));
+        assert_eq!(config.advice_timeout, Duration::from_secs(12));
+        assert_eq!(config.read_timeout, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn malformed() {
+        for vars in [
+            [("BIND_ADDR", "localhost")],
+            [("BIND_ADDR", "")],
+            [("SCRAPBOOK_ADVICE_TIMEOUT", "5s")],
+            [("SCRAPBOOK_ADVICE_TIMEOUT", "-1")],
+            [("READ_TIMEOUT", "1.5")],
+            [("CRAWL_HANDOUT_LIMIT", "-1")],
+            [("CRAWL_HANDOUT_LIMITS", "s1.sfgame.net")],
+            [("CRAWL_HANDOUT_LIMITS", "s1.sfgame.net=fast")],
+        ] {
+            let err = Config::from_pairs(&vars).unwrap_err();
+            assert!(err.starts_with(vars[0].0), "{err}");
+        }
+    }
+
+    #[test]
+    fn allowed_game_domains() {
+        let config = Config::from_pairs(&[(
+            "ALLOWED_GAME_DOMAINS",
+            " sfgame.net, SFGame.EU,,.playa-games.com ",
+        )])
+        .unwrap();
+        let domains = config.allowed_game_domains.as_deref().unwrap();
+        assert_eq!(domains, ["sfgame.net", "sfgame.eu", "playa-games.com"]);
+        assert!(config.allows_server("s1.sfgame.net"));
+        assert!(config.allows_server("https://F2.SFGAME.EU/"));
+        assert!(config.allows_server("w3.playa-games.com"));
+        assert!(!config.allows_server("s1.fakegame.net"));
+        assert!(!config.allows_server("sfgame.net.example.com"));
+        assert!(!config.allows_server(""));
+
+        for val in ["", " , ,"] {
+            let err = Config::from_pairs(&[("ALLOWED_GAME_DOMAINS", val)])
+                .unwrap_err();
+            assert_eq!(
+                err,
+                "ALLOWED_GAME_DOMAINS must contain at least one domain"
+            );
+        }
+    }
+
+    #[test]
+    fn handout_limits() {
+        let config = Config::from_pairs(&[
+            ("CRAWL_HANDOUT_LIMIT", "500"),
+            (
+                "CRAWL_HANDOUT_LIMITS",
+                "S1.sfgame.net=100, s2.sfgame.net = 0,",
+            ),
+        ])
+        .unwrap();
+        assert_eq!(config.handout_limit("s1.sfgame.net"), Some(100));
+        assert_eq!(config.handout_limit("s2.sfgame.net"), Some(0));
+        assert_eq!(config.handout_limit("s3.sfgame.net"), Some(500));
+
+        let config = Config::from_pairs(&[(
+            "CRAWL_HANDOUT_LIMITS",
+            "s1.sfgame.net=100",
+        )])
+        .unwrap();
+        assert_eq!(config.handout_limit("s1.sfgame.net"), Some(100));
+        assert_eq!(config.handout_limit("s2.sfgame.net"), None);
+    }
+
+    #[test]
+    fn zero_timeouts() {
+        for name in ["SCRAPBOOK_ADVICE_TIMEOUT", "READ_TIMEOUT"] {
+            let err = Config::from_pairs(&[(name, "0")]).unwrap_err();
+            assert_eq!(err, format!("{name} must be at least 1 second"));
+        }
+    }
+
+    #[test]
+    fn not_unicode() {
+        let err = Config::from_vars(|name| match name {
+            "READ_TIMEOUT" => Err(VarError::NotUnicode(OsString::from("x"))),
+            _ => Err(VarError::NotPresent),
+        })
+        .unwrap_err();
+        assert!(err.starts_with("READ_TIMEOUT can not be read"), "{err}");
+    }
+}
@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::{FromRequest, rejection::JsonRejection},
+    http::{StatusCode, header::RETRY_AFTER},
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use sf_info_lib::error::SFSError;
+
+/// Works just like [`Json`], but rejects malformed bodies with the same error
+/// body as every other error
+#[derive(FromRequest)]
+#[from_request(via(Json), rejection(InvalidInput))]
+pub struct Input<T>(pub T);
+
+/// The rejection of [`Input`], when the body is not the JSON we expect
+pub struct InvalidInput(Response);
+
+impl From<JsonRejection> for InvalidInput {
+    fn from(value: JsonRejection) -> Self {
+        InvalidInput(error_response(
+            value.status(),
+            ErrorCode::InvalidInput,
+            value.body_text(),
+        ))
+    }
+}
+
+impl IntoResponse for InvalidInput {
+    fn into_response(self) -> Response {
+        self.0
+    }
+}
+
+/// The stable, machine readable `error` of an [`ErrorBody`]. These are
+/// serialized in snake_case, so `InvalidScrapbook` becomes
+/// `"invalid_scrapbook"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request itself was malformed, or contained values the game could
+    /// never produce
+    InvalidInput,
+    InvalidScrapbook,
+    InvalidServer,
+    InvalidPlayer,
+    Database,
+    /// The request took too long and should be retried later
+    Timeout,
+    Internal,
+}
+
+impl ErrorCode {
+    /// The status, that errors with this code are usually returned with.
+    /// Mistakes on the client side are 4xx, everything else 5xx
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InvalidInput
+            | ErrorCode::InvalidScrapbook
+            | ErrorCode::InvalidServer
+            | ErrorCode::InvalidPlayer => StatusCode::BAD_REQUEST,
+            ErrorCode::Database | ErrorCode::Internal => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ErrorCode::Timeout => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+impl From<&SFSError> for ErrorCode {
+    fn from(value: &SFSError) -> Self {
+        match value {
+            SFSError::InvalidScrapbook { .. } => ErrorCode::InvalidScrapbook,
+            SFSError::InvalidServer { .. } => ErrorCode::InvalidServer,
+            SFSError::InvalidPlayer { .. } => ErrorCode::InvalidPlayer,
+            SFSError::DBError { .. } => ErrorCode::Database,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+/// The body of every error response. `error` is a stable code, that clients
+/// can branch on, `message` is meant for humans and may change at any time
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub error: ErrorCode,
+    pub message: String,
+}
+
+pub fn error_response(
+    status: StatusCode,
+    error: ErrorCode,
+    message: impl Into<String>,
+) -> Response {
+    let body = ErrorBody {
+        error,
+        message: message.into(),
+    };
+    (status, Json(body)).into_response()
+}
+
+pub fn to_response(value: SFSError) -> Response {
+    let code = ErrorCode::from(&value);
+    error_response(code.status(), code, value.to_string())
+}
+
+/// The response for requests, that contain values the game could never
+/// produce. These are mistakes on the client side, so we tell them as much
+pub fn bad_request(msg: impl Into<String>) -> Response {
+    error_response(StatusCode::BAD_REQUEST, ErrorCode::InvalidInput, msg)
+}
+
+/// The response for requests, that were cancelled because they took too long.
+/// The request itself is most likely fine, so the client should just retry.
+/// We ask them to wait as long as the request was allowed to take, so that
+/// retries do not pile up faster than timed out queries finish
+pub fn timeout_response(timeout: Duration) -> Response {
+    let retry_after = timeout.as_secs().max(1).to_string();
+    (
+        [(RETRY_AFTER, retry_after)],
+        error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Timeout,
+            "The request took too long. Please try again later",
+        ),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+pub async fn body_json(res: Response) -> serde_json::Value {
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Method, Request, header::CONTENT_TYPE},
+    };
+
+    use super::*;
+
+    async fn parse_input(content_type: &str, body: &'static str) -> Response {
+        let req = Request::builder()
+            .method(Method::POST)
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap();
+        match Input::<Vec<u32>>::from_request(req, &()).await {
+            Ok(_) => panic!("{body} should have been rejected"),
+            Err(e) => e.into_response(),
+        }
+    }
+
+    #[tokio::test]
+    async fn error_body_shape() {
+        for (code, expected) in [
+            (ErrorCode::InvalidInput, "invalid_input"),
+            (ErrorCode::InvalidScrapbook, "invalid_scrapbook"),
+            (ErrorCode::InvalidServer, "invalid_server"),
+            (ErrorCode::InvalidPlayer, "invalid_player"),
+            (ErrorCode::Database, "database"),
+            (ErrorCode::Timeout, "timeout"),
+            (ErrorCode::Internal, "internal"),
+        ] {
+            let res = error_response(StatusCode::IM_A_TEAPOT, code, "short");
+            assert_eq!(res.status(), StatusCode::IM_A_TEAPOT);
+            assert_eq!(
+                body_json(res).await,
+                serde_json::json!({ "error": expected, "message": "short" })
+            );
+        }
+    }
+
+    #[test]
+    fn error_status() {
+        for (code, status) in [
+            (ErrorCode::InvalidInput, StatusCode::BAD_REQUEST),
+            (ErrorCode::InvalidScrapbook, StatusCode::BAD_REQUEST),
+            (ErrorCode::InvalidServer, StatusCode::BAD_REQUEST),
+            (ErrorCode::InvalidPlayer, StatusCode::BAD_REQUEST),
+            (ErrorCode::Database, StatusCode::INTERNAL_SERVER_ERROR),
+            (ErrorCode::Timeout, StatusCode::SERVICE_UNAVAILABLE),
+            (ErrorCode::Internal, StatusCode::INTERNAL_SERVER_ERROR),
+        ] {
+            assert_eq!(code.status(), status, "{code:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn bad_request_body() {
+        let res = bad_request("nope");
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_json(res).await,
+            serde_json::json!({ "error": "invalid_input", "message": "nope" })
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout_body() {
+        let res = timeout_response(Duration::from_secs(30));
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[RETRY_AFTER], "30");
+        assert_eq!(body_json(res).await["error"], "timeout");
+    }
+
+    #[tokio::test]
+    async fn malformed_json_is_invalid_input() {
+        let res = parse_input("application/json", "[1, 2").await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(body_json(res).await["error"], "invalid_input");
+    }
+
+    #[tokio::test]
+    async fn wrong_json_type_is_invalid_input() {
+        let res = parse_input("application/json", r#"{"a": 1}"#).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body_json(res).await["error"], "invalid_input");
+    }
+
+    #[tokio::test]
+    async fn wrong_content_type_is_invalid_input() {
+        let res = parse_input("text/plain", "[1, 2]").await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(body_json(res).await["error"], "invalid_input");
+    }
+}
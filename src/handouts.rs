@@ -0,0 +1,100 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// The window, that handout limits apply to
+pub const HANDOUT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts the players and HoF pages handed out to crawlers per game server, so
+/// that the whole fleet of crawlers can not hammer a single server. This is
+/// only tracked in memory, so a restart resets every window
+#[derive(Debug, Default)]
+pub struct HandoutLimiter {
+    servers: Mutex<HashMap<String, Window>>,
+}
+
+#[derive(Debug)]
+struct Window {
+    start: Instant,
+    handed_out: u32,
+}
+
+impl HandoutLimiter {
+    /// Checks if `server` has already been handed out `limit` or more items in
+    /// the current window
+    pub fn is_exhausted(&self, server: &str, limit: u32, now: Instant) -> bool {
+        let servers =
+            self.servers.lock().unwrap_or_else(PoisonError::into_inner);
+        match servers.get(server) {
+            Some(window) if !window.has_ended(now) => {
+                window.handed_out >= limit
+            }
+            _ => limit == 0,
+        }
+    }
+
+    /// Adds `count` handed out items to the current window of `server`.
+    ///
+    /// Handouts are only recorded after the library returned them, so
+    /// concurrent requests can overshoot the limit by up to one batch each
+    pub fn record(&self, server: &str, count: usize, now: Instant) {
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+        let mut servers =
+            self.servers.lock().unwrap_or_else(PoisonError::into_inner);
+        let window = servers.entry(server.to_string()).or_insert(Window {
+            start: now,
+            handed_out: 0,
+        });
+        if window.has_ended(now) {
+            *window = Window {
+                start: now,
+                handed_out: 0,
+            };
+        }
+        window.handed_out = window.handed_out.saturating_add(count);
+    }
+}
+
+impl Window {
+    fn has_ended(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.start) >= HANDOUT_WINDOW
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exceeding_the_limit() {
+        let limiter = HandoutLimiter::default();
+        let start = Instant::now();
+        assert!(!limiter.is_exhausted("s1.sfgame.net", 100, start));
+
+        limiter.record("s1.sfgame.net", 60, start);
+        assert!(!limiter.is_exhausted("s1.sfgame.net", 100, start));
+        limiter.record("s1.sfgame.net", 60, start);
+        assert!(limiter.is_exhausted("s1.sfgame.net", 100, start));
+
+        // Other servers have their own window
+        assert!(!limiter.is_exhausted("s2.sfgame.net", 100, start));
+
+        let later = start + HANDOUT_WINDOW - Duration::from_secs(1);
+        assert!(limiter.is_exhausted("s1.sfgame.net", 100, later));
+
+        let next = start + HANDOUT_WINDOW;
+        assert!(!limiter.is_exhausted("s1.sfgame.net", 100, next));
+        limiter.record("s1.sfgame.net", 99, next);
+        assert!(!limiter.is_exhausted("s1.sfgame.net", 100, next));
+        limiter.record("s1.sfgame.net", 1, next);
+        assert!(limiter.is_exhausted("s1.sfgame.net", 100, next));
+    }
+
+    #[test]
+    fn zero_limit() {
+        let limiter = HandoutLimiter::default();
+        assert!(limiter.is_exhausted("s1.sfgame.net", 0, Instant::now()));
+    }
+}
@@ -1,14 +1,33 @@
-use std::sync::Arc;
+mod config;
+mod error;
+mod handouts;
+mod servers;
+
+use std::{
+    ops::RangeInclusive,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use axum::{
     Json, Router,
+    extract::State,
     http::{
-        Method, StatusCode,
-        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderMap, Method, StatusCode,
+        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
     },
-    response::Response,
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
+use config::Config;
+use error::{
+    ErrorBody, ErrorCode, Input, bad_request, error_response, timeout_response,
+    to_response,
+};
+use handouts::HandoutLimiter;
+use serde::{Deserialize, Serialize};
+use servers::server_host;
+use sf_api::gamestate::unlockables::ScrapBook;
 use sf_info_lib::{
     db::{get_characters_to_crawl, underworld::get_best_nude_players, *},
     error::SFSError,
@@ -16,7 +35,10 @@ use sf_info_lib::{
 };
 #[cfg(not(target_env = "msvc"))]
 use tikv_jemallocator::Jemalloc;
-use tower_http::cors::{Any, CorsLayer};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{Any, CorsLayer},
+};
 
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
@@ -26,85 +48,737 @@ static GLOBAL: Jemalloc = Jemalloc;
 async fn main() -> Result<(), Box<dyn core::error::Error>> {
     tracing_subscriber::fmt::init();
 
+    let config = Config::from_env()?;
+    tracing::info!(
+        bind_addr = %config.bind_addr,
+        advice_timeout = ?config.advice_timeout,
+        read_timeout = ?config.read_timeout,
+        allowed_game_domains = ?config.allowed_game_domains,
+        handout_limit = ?config.handout_limit,
+        handout_limits = ?config.handout_limits,
+        "Starting"
+    );
+    let bind_addr = config.bind_addr;
+
     let cors = CorsLayer::new()
         .allow_methods([Method::GET, Method::POST])
         .allow_headers([CONTENT_TYPE, AUTHORIZATION])
         .allow_origin(Any);
 
-    let app = Router::new()
-        .route("/", get(root))
+    // The advice responses are the only ones, that get large enough to be
+    // worth compressing
+    let advice = Router::new()
         .route("/scrapbook_advice", post(scrapbook_advice))
         .route("/underworld_advice", post(underworld_advice))
+        .layer(compression());
+
+    let app = Router::new()
+        .route("/", get(root))
+        .merge(advice)
         .route("/get_crawl_hof_pages", post(get_crawl_hof_pages))
         .route("/get_crawl_players", post(get_crawl_chars))
         .route("/report_players", post(report_players))
         .route("/report_hof", post(report_hof_pages))
+        .route("/report_all", post(report_all))
         .route("/report", post(report_bug))
-        .layer(cors);
+        .route("/validate_scrapbook", post(validate_scrapbook))
+        .layer(cors)
+        .with_state(Arc::new(AppState {
+            config,
+            handouts: HandoutLimiter::default(),
+        }));
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:4949").await?;
+    let listener = tokio::net::TcpListener::bind(bind_addr).await?;
     Ok(axum::serve(listener, app).await?)
 }
 
+/// Compresses responses with gzip or zstd, if the client accepts either
+fn compression() -> CompressionLayer {
+    CompressionLayer::new().gzip(true).zstd(true)
+}
+
+/// Everything the handlers share
+struct AppState {
+    config: Config,
+    handouts: HandoutLimiter,
+}
+
 async fn root() -> axum::response::Redirect {
     axum::response::Redirect::permanent("https://forum.mfbot.de/")
 }
 
 async fn report_players(
-    Json(report): Json<CrawlReport>,
+    State(state): State<Arc<AppState>>,
+    Input(report): Input<CrawlReport>,
 ) -> Result<(), Response> {
+    check_server(&state.config, report.server.as_str())?;
     handle_crawl_report(report).await.map_err(to_response)
 }
 
+/// Rejects servers outside of `ALLOWED_GAME_DOMAINS`, before the library gets
+/// a chance to create a row for them
+fn check_server(config: &Config, server: &str) -> Result<(), Response> {
+    if config.allows_server(server) {
+        return Ok(());
+    }
+    tracing::warn!(server, "Rejecting server outside of the allowed domains");
+    Err(error_response(
+        StatusCode::BAD_REQUEST,
+        ErrorCode::InvalidServer,
+        format!("{server} is not on an allowed game domain"),
+    ))
+}
+
+/// No server has anywhere near this many players. Anything above this is a
+/// broken client, that would have us enqueue an absurd amount of HoF pages
+const MAX_PLAYER_COUNT: u64 = 5_000_000;
+
+/// The amount of players on a single page of the HoF
+const HOF_PAGE_SIZE: u64 = 51;
+
+/// Rounds `player_count` up to the next multiple of [`HOF_PAGE_SIZE`].
+///
+/// `get_hof_pages_to_crawl` enqueues `player_count / 51` pages, which drops
+/// the last, partial page. It only uses `player_count` to compute that page
+/// total, so handing it the rounded count makes it enqueue exactly the pages,
+/// that exist. If the library ever uses the count for anything else, this
+/// has to move into the library
+fn round_up_to_full_pages<T: TryFrom<u64>>(
+    player_count: u64,
+) -> Result<T, Response> {
+    T::try_from(player_count.div_ceil(HOF_PAGE_SIZE) * HOF_PAGE_SIZE)
+        .map_err(|_| bad_request("player_count is too large"))
+}
+
 async fn get_crawl_hof_pages(
-    Json(args): Json<GetHofArgs>,
+    State(state): State<Arc<AppState>>,
+    Input(mut args): Input<GetHofArgs>,
 ) -> Result<Json<Vec<i32>>, Response> {
-    get_hof_pages_to_crawl(args)
+    check_server(&state.config, args.server.as_str())?;
+    let player_count =
+        check_range("player_count", args.player_count, 0..=MAX_PLAYER_COUNT)
+            .inspect_err(|_| {
+                tracing::warn!(
+                    player_count = %args.player_count,
+                    "Rejecting bogus player count"
+                );
+            })?;
+    args.player_count = round_up_to_full_pages(player_count)?;
+    let server = args.server.as_str().to_owned();
+    limit_handouts(&state, &server, get_hof_pages_to_crawl(args))
         .await
-        .map_err(to_response)
         .map(Json)
 }
 
 async fn report_hof_pages(
-    Json(args): Json<ReportHofArgs>,
+    State(state): State<Arc<AppState>>,
+    Input(args): Input<ReportHofArgs>,
 ) -> Result<(), Response> {
+    check_server(&state.config, args.server.as_str())?;
     insert_hof_pages(args).await.map_err(to_response)
 }
 
+/// Everything a crawler has collected in one tick, so that it does not need a
+/// request per kind of report. Either part may be left out
+#[derive(Deserialize)]
+pub struct CombinedReport {
+    pub players: Option<CrawlReport>,
+    pub hof_pages: Option<ReportHofArgs>,
+}
+
+/// The outcome of every part of a [`CombinedReport`]
+#[derive(Debug, Serialize)]
+pub struct CombinedReportSummary {
+    pub players: ReportOutcome,
+    pub hof_pages: ReportOutcome,
+}
+
+impl CombinedReportSummary {
+    /// The name, error and message of the first part, that failed
+    fn first_failure(&self) -> Option<(&'static str, ErrorCode, &str)> {
+        [("players", &self.players), ("hof_pages", &self.hof_pages)]
+            .into_iter()
+            .find_map(|(part, outcome)| match outcome {
+                ReportOutcome::Failed { error, message } => {
+                    Some((part, *error, message.as_str()))
+                }
+                _ => None,
+            })
+    }
+}
+
+impl IntoResponse for CombinedReportSummary {
+    fn into_response(self) -> Response {
+        let Some((error, message)) =
+            self.first_failure().map(|(part, error, message)| {
+                (error, format!("{part}: {message}"))
+            })
+        else {
+            return Json(self).into_response();
+        };
+        let body = CombinedReportError {
+            error: ErrorBody { error, message },
+            summary: self,
+        };
+        (error.status(), Json(body)).into_response()
+    }
+}
+
+/// The body of a combined report, that has failed. This is the usual
+/// [`ErrorBody`] for the first failed part, with the outcome of every part
+/// next to it
+#[derive(Serialize)]
+struct CombinedReportError {
+    #[serde(flatten)]
+    error: ErrorBody,
+    #[serde(flatten)]
+    summary: CombinedReportSummary,
+}
+
+/// Serialized with a `status` tag, so `{"status": "accepted"}`, or
+/// `{"status": "failed", "error": "database", "message": "..."}`
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ReportOutcome {
+    /// This part was not part of the report
+    Skipped,
+    Accepted,
+    Failed {
+        error: ErrorCode,
+        message: String,
+    },
+}
+
+/// Stores every part of the report and responds with the outcome of each.
+///
+/// If any part failed, this responds with the error of the first failed part,
+/// so that crawlers retry it. Parts, that were accepted, are already stored at
+/// that point. Retrying the whole report sends them again, so crawlers should
+/// only resend the parts with a `failed` status. Reports for servers outside
+/// of the allowed domains are rejected as a whole, before anything is stored
+async fn report_all(
+    State(state): State<Arc<AppState>>,
+    Input(report): Input<CombinedReport>,
+) -> Result<CombinedReportSummary, Response> {
+    if let Some(players) = &report.players {
+        check_server(&state.config, players.server.as_str())?;
+    }
+    if let Some(hof_pages) = &report.hof_pages {
+        check_server(&state.config, hof_pages.server.as_str())?;
+    }
+
+    // These run one after the other, so that the player report creates the
+    // server, before the HoF pages need it. Doing both at once would have them
+    // race to create a server, that we have never seen before
+    let players = report_outcome(report.players.map(handle_crawl_report)).await;
+    let hof_pages =
+        report_outcome(report.hof_pages.map(insert_hof_pages)).await;
+
+    Ok(CombinedReportSummary { players, hof_pages })
+}
+
+async fn report_outcome(
+    report: Option<impl Future<Output = Result<(), SFSError>>>,
+) -> ReportOutcome {
+    match report {
+        None => ReportOutcome::Skipped,
+        Some(report) => match report.await {
+            Ok(()) => ReportOutcome::Accepted,
+            Err(e) => ReportOutcome::Failed {
+                error: ErrorCode::from(&e),
+                message: e.to_string(),
+            },
+        },
+    }
+}
+
 async fn get_crawl_chars(
-    Json(args): Json<GetCharactersArgs>,
+    State(state): State<Arc<AppState>>,
+    Input(args): Input<GetCharactersArgs>,
 ) -> Result<Json<Vec<String>>, Response> {
-    get_characters_to_crawl(args)
+    check_server(&state.config, args.server.as_str())?;
+    let server = args.server.as_str().to_owned();
+    limit_handouts(&state, &server, get_characters_to_crawl(args))
         .await
-        .map_err(to_response)
         .map(Json)
 }
 
+/// Hands out the crawl work of `handout`, unless `server` has already reached
+/// its handout limit for this minute. In that case crawlers get nothing to do
+/// and the library is not even asked for work
+async fn limit_handouts<T>(
+    state: &AppState,
+    server: &str,
+    handout: impl Future<Output = Result<Vec<T>, SFSError>>,
+) -> Result<Vec<T>, Response> {
+    let host = server_host(server);
+    let Some(limit) = state.config.handout_limit(&host) else {
+        return handout.await.map_err(to_response);
+    };
+    if state.handouts.is_exhausted(&host, limit, Instant::now()) {
+        return Ok(Vec::new());
+    }
+    let work = handout.await.map_err(to_response)?;
+    state.handouts.record(&host, work.len(), Instant::now());
+    Ok(work)
+}
+
+/// The highest level we accept in requests. The game itself caps out well
+/// below this
+const MAX_LEVEL: u64 = 1_000;
+/// The highest amount of attributes we accept in requests
+const MAX_ATTRS: u64 = 1_000_000_000;
+
+/// Makes sure, that `value` is a number in `range`. Negative numbers and
+/// numbers too large for any player are mistakes on the client side
+fn check_range(
+    name: &str,
+    value: impl TryInto<u64>,
+    range: RangeInclusive<u64>,
+) -> Result<u64, Response> {
+    value
+        .try_into()
+        .ok()
+        .filter(|v| range.contains(v))
+        .ok_or_else(|| {
+            bad_request(format!(
+                "{name} must be between {} and {}",
+                range.start(),
+                range.end()
+            ))
+        })
+}
+
+/// The content types clients can `Accept`, to receive scrapbook advice as
+/// MessagePack instead of JSON. See the README for the layout
+const MSGPACK_TYPES: [&str; 2] =
+    ["application/msgpack", "application/x-msgpack"];
+
+/// Looks at the media ranges of an `Accept` header and returns the
+/// MessagePack content type to respond with, if the client prefers it over
+/// JSON. MessagePack has to be asked for explicitly, so it wins ties, like in
+/// `application/msgpack, */*`
+fn preferred_msgpack(accept: &str) -> Option<&'static str> {
+    let mut msgpack: Option<(&'static str, f32)> = None;
+    let mut json = None;
+    let mut wildcard = 0.0f32;
+    for range in accept.split(',') {
+        let mut params = range.split(';');
+        let media = params.next().unwrap_or_default().trim();
+        let q = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if let Some(ty) =
+            MSGPACK_TYPES.iter().find(|a| a.eq_ignore_ascii_case(media))
+        {
+            if msgpack.is_none_or(|(_, best)| q > best) {
+                msgpack = Some((*ty, q));
+            }
+        } else if media.eq_ignore_ascii_case("application/json") {
+            json = Some(q);
+        } else if media == "*/*" || media.eq_ignore_ascii_case("application/*")
+        {
+            wildcard = wildcard.max(q);
+        }
+    }
+    let json = json.unwrap_or(wildcard);
+    msgpack
+        .filter(|&(_, q)| q > 0.0 && q >= json)
+        .map(|(ty, _)| ty)
+}
+
+/// Responds with JSON by default, or MessagePack, if the client prefers that
 async fn scrapbook_advice(
-    Json(args): Json<ScrapBookAdviceArgs>,
-) -> Result<Json<Arc<[ScrapBookAdvice]>>, Response> {
-    get_scrapbook_advice(args)
-        .await
-        .map_err(to_response)
-        .map(Json)
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Input(args): Input<ScrapBookAdviceArgs>,
+) -> Result<Response, Response> {
+    check_range("max_level", args.max_level, 1..=MAX_LEVEL)?;
+    check_range("max_attrs", args.max_attrs, 1..=MAX_ATTRS)?;
+    let advice =
+        with_timeout(state.config.advice_timeout, get_scrapbook_advice(args))
+            .await?;
+
+    let Some(msgpack) = headers
+        .get(ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .and_then(preferred_msgpack)
+    else {
+        return Ok(Json(advice).into_response());
+    };
+    let body = rmp_serde::to_vec_named(&*advice).map_err(|e| {
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::Internal,
+            e.to_string(),
+        )
+    })?;
+    Ok(([(CONTENT_TYPE, msgpack)], body).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct ValidateScrapbookArgs {
+    pub raw_scrapbook: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScrapbookValidation {
+    pub valid: bool,
+    pub item_count: u32,
+    /// Why the scrapbook could not be parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<&'static str>,
+}
+
+/// Checks if a scrapbook can be parsed, without touching the database, so
+/// that clients can catch encoding problems before asking for advice
+async fn validate_scrapbook(
+    Input(args): Input<ValidateScrapbookArgs>,
+) -> Json<ScrapbookValidation> {
+    Json(check_scrapbook(&args.raw_scrapbook))
+}
+
+fn check_scrapbook(raw: &str) -> ScrapbookValidation {
+    let invalid = |reason| ScrapbookValidation {
+        valid: false,
+        item_count: 0,
+        reason: Some(reason),
+    };
+    if raw.trim().is_empty() {
+        return invalid("The scrapbook is empty");
+    }
+    // The game uses the standard base64 alphabet. Clients, that encode the
+    // scrapbook themselves, sometimes send the URL safe one instead
+    if raw.contains(['-', '_']) {
+        return invalid(
+            "The scrapbook uses URL safe base64, but the game uses the \
+             standard alphabet with '+' and '/'",
+        );
+    }
+    match ScrapBook::parse(raw) {
+        Some(scrapbook) => ScrapbookValidation {
+            valid: true,
+            item_count: u32::try_from(scrapbook.items.len())
+                .unwrap_or(u32::MAX),
+            reason: None,
+        },
+        None => invalid("The scrapbook is not valid base64 scrapbook data"),
+    }
 }
 
 async fn underworld_advice(
-    Json(args): Json<UnderworldAdviceArgs>,
+    State(state): State<Arc<AppState>>,
+    Input(args): Input<UnderworldAdviceArgs>,
 ) -> Result<Json<Arc<[UnderworldAdvice]>>, Response> {
-    get_best_nude_players(args)
+    with_timeout(state.config.read_timeout, get_best_nude_players(args))
         .await
-        .map_err(to_response)
         .map(Json)
 }
 
-async fn report_bug(Json(args): Json<BugReportArgs>) -> Result<(), Response> {
+/// Gives up on the query, if it has not finished after `timeout`.
+///
+/// This only bounds how long the client has to wait. Dropping the future does
+/// not cancel the query in Postgres, so the backend and its pool connection
+/// stay busy until the query finishes on its own. Actually stopping the query
+/// requires a `statement_timeout` on the transaction in sf-info-lib
+async fn with_timeout<T>(
+    timeout: Duration,
+    query: impl Future<Output = Result<T, SFSError>>,
+) -> Result<T, Response> {
+    tokio::time::timeout(timeout, query)
+        .await
+        .map_err(|_| timeout_response(timeout))?
+        .map_err(to_response)
+}
+
+async fn report_bug(Input(args): Input<BugReportArgs>) -> Result<(), Response> {
     insert_bug(args).await.map_err(to_response)
 }
 
-pub fn to_response(value: SFSError) -> axum::response::Response {
-    axum::response::IntoResponse::into_response((
-        StatusCode::INTERNAL_SERVER_ERROR,
-        value.to_string(),
-    ))
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use axum::{
+        body::{Body, to_bytes},
+        http::{
+            Request,
+            header::{ACCEPT_ENCODING, CONTENT_ENCODING, RETRY_AFTER},
+        },
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::error::body_json;
+
+    #[tokio::test]
+    async fn slow_queries_time_out() {
+        let res = with_timeout(
+            Duration::from_millis(1),
+            std::future::pending::<Result<(), SFSError>>(),
+        )
+        .await;
+        let res = res.unwrap_err();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[RETRY_AFTER], "1");
+    }
+
+    #[tokio::test]
+    async fn fast_queries_finish() {
+        let res = with_timeout(Duration::from_secs(5), async {
+            Ok::<_, SFSError>(1)
+        })
+        .await;
+        assert_eq!(res.ok(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn advice_bounds() {
+        assert_eq!(check_range("max_level", 1, 1..=MAX_LEVEL).ok(), Some(1));
+        assert_eq!(
+            check_range("max_attrs", 50_000u32, 1..=MAX_ATTRS).ok(),
+            Some(50_000)
+        );
+        assert_eq!(
+            check_range("max_level", MAX_LEVEL, 1..=MAX_LEVEL).ok(),
+            Some(MAX_LEVEL)
+        );
+
+        for bad in [0, -1, i64::from(i32::MIN), 1_001, i64::MAX] {
+            let res = check_range("max_level", bad, 1..=MAX_LEVEL).unwrap_err();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+        assert!(check_range("max_attrs", u64::MAX, 1..=MAX_ATTRS).is_err());
+        assert!(check_range("max_attrs", -5i32, 1..=MAX_ATTRS).is_err());
+
+        let res = check_range("max_level", 0, 1..=MAX_LEVEL).unwrap_err();
+        assert_eq!(
+            body_json(res).await["message"],
+            "max_level must be between 1 and 1000"
+        );
+    }
+
+    #[test]
+    fn player_count_bounds() {
+        let check = |count: i64| {
+            check_range("player_count", count, 0..=MAX_PLAYER_COUNT)
+        };
+        assert_eq!(check(0).ok(), Some(0));
+        assert_eq!(check(4_000_000).ok(), Some(4_000_000));
+        for bad in [-1, 5_000_001, 2_000_000_000, i64::MAX] {
+            let res = check(bad).unwrap_err();
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[test]
+    fn player_counts_round_up_to_full_pages() {
+        for (player_count, rounded) in
+            [(0, 0), (1, 51), (50, 51), (51, 51), (52, 102), (100, 102)]
+        {
+            let res: u64 = round_up_to_full_pages(player_count).ok().unwrap();
+            assert_eq!(res, rounded, "{player_count} players");
+        }
+        let res = round_up_to_full_pages::<u8>(300).unwrap_err();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn disallowed_servers() {
+        let config =
+            Config::from_pairs(&[("ALLOWED_GAME_DOMAINS", "sfgame.net")])
+                .unwrap();
+        assert!(check_server(&config, "s1.sfgame.net").is_ok());
+
+        let res = check_server(&config, "s1.example.com").unwrap_err();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body_json(res).await,
+            serde_json::json!({
+                "error": "invalid_server",
+                "message": "s1.example.com is not on an allowed game domain",
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn exceeding_the_handout_limit() {
+        let state = AppState {
+            config: Config::from_pairs(&[("CRAWL_HANDOUT_LIMIT", "3")])
+                .unwrap(),
+            handouts: HandoutLimiter::default(),
+        };
+        let asked = &AtomicBool::new(false);
+        let handout = |work: Vec<u32>| async move {
+            asked.store(true, Ordering::Relaxed);
+            Ok::<_, SFSError>(work)
+        };
+
+        let res = limit_handouts(&state, "s1.sfgame.net", handout(vec![1, 2]));
+        assert_eq!(res.await.ok(), Some(vec![1, 2]));
+        let res = limit_handouts(&state, "s1.sfgame.net", handout(vec![3, 4]));
+        assert_eq!(res.await.ok(), Some(vec![3, 4]));
+
+        asked.store(false, Ordering::Relaxed);
+        let res =
+            limit_handouts(&state, "https://S1.sfgame.net/", handout(vec![5]));
+        assert_eq!(res.await.ok(), Some(vec![]));
+        assert!(!asked.load(Ordering::Relaxed));
+
+        let res = limit_handouts(&state, "s2.sfgame.net", handout(vec![6]));
+        assert_eq!(res.await.ok(), Some(vec![6]));
+        assert!(asked.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn compressed_responses() {
+        let advice: Vec<u32> = (0..1_000).collect();
+        let expected = serde_json::to_vec(&advice).unwrap();
+        let app = Router::new()
+            .route("/", get(move || async move { Json(advice) }))
+            .layer(compression());
+
+        for encoding in ["gzip", "zstd"] {
+            let req = Request::get("/")
+                .header(ACCEPT_ENCODING, encoding)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(res.headers()[CONTENT_ENCODING], encoding);
+
+            let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+            assert!(body.len() < expected.len(), "{encoding}");
+            let decoded = match encoding {
+                "gzip" => {
+                    let mut decoded = Vec::new();
+                    flate2::read::GzDecoder::new(&body[..])
+                        .read_to_end(&mut decoded)
+                        .unwrap();
+                    decoded
+                }
+                _ => zstd::decode_all(&body[..]).unwrap(),
+            };
+            assert_eq!(decoded, expected, "{encoding}");
+        }
+
+        let req = Request::get("/").body(Body::empty()).unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn invalid_scrapbooks() {
+        for (raw, reason) in [
+            ("", "The scrapbook is empty"),
+            ("  ", "The scrapbook is empty"),
+            ("ab-c_def", "The scrapbook uses URL safe base64"),
+            ("not base64!", "The scrapbook is not valid base64"),
+        ] {
+            let res = check_scrapbook(raw);
+            assert!(!res.valid, "{raw:?}");
+            assert_eq!(res.item_count, 0);
+            assert!(res.reason.unwrap().starts_with(reason), "{raw:?}");
+        }
+
+        let res = validate_scrapbook(Input(ValidateScrapbookArgs {
+            raw_scrapbook: String::new(),
+        }))
+        .await;
+        assert_eq!(
+            body_json(res.into_response()).await,
+            serde_json::json!({
+                "valid": false,
+                "item_count": 0,
+                "reason": "The scrapbook is empty",
+            })
+        );
+    }
+
+    #[test]
+    fn msgpack_negotiation() {
+        let cases = [
+            ("application/msgpack", Some("application/msgpack")),
+            ("application/x-msgpack", Some("application/x-msgpack")),
+            ("Application/MsgPack", Some("application/msgpack")),
+            ("application/msgpack, */*", Some("application/msgpack")),
+            ("application/msgpack;q=0, application/json", None),
+            ("application/msgpack;q=0", None),
+            ("application/json, application/msgpack;q=0.5", None),
+            (
+                "application/json;q=0.1, application/msgpack",
+                Some("application/msgpack"),
+            ),
+            (
+                "application/json;q=0, */*, application/msgpack;q=0.5",
+                Some("application/msgpack"),
+            ),
+            ("application/json", None),
+            ("*/*", None),
+            ("application/msgpackage", None),
+            ("", None),
+        ];
+        for (accept, expected) in cases {
+            assert_eq!(preferred_msgpack(accept), expected, "{accept}");
+        }
+    }
+
+    #[tokio::test]
+    async fn combined_report_summary() {
+        use ReportOutcome::*;
+
+        let summary = CombinedReportSummary {
+            players: Accepted,
+            hof_pages: Skipped,
+        };
+        let res = summary.into_response();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            body_json(res).await,
+            serde_json::json!({
+                "players": { "status": "accepted" },
+                "hof_pages": { "status": "skipped" },
+            })
+        );
+
+        let failed = |error, message: &str| Failed {
+            error,
+            message: message.to_string(),
+        };
+        let summary = CombinedReportSummary {
+            players: Accepted,
+            hof_pages: failed(ErrorCode::Database, "gone"),
+        };
+        let res = summary.into_response();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            body_json(res).await,
+            serde_json::json!({
+                "error": "database",
+                "message": "hof_pages: gone",
+                "players": { "status": "accepted" },
+                "hof_pages": {
+                    "status": "failed",
+                    "error": "database",
+                    "message": "gone",
+                },
+            })
+        );
+
+        let summary = CombinedReportSummary {
+            players: failed(ErrorCode::InvalidPlayer, "bad"),
+            hof_pages: failed(ErrorCode::Database, "gone"),
+        };
+        let res = summary.into_response();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = body_json(res).await;
+        assert_eq!(body["error"], "invalid_player");
+        assert_eq!(body["message"], "players: bad");
+    }
 }
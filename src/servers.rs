@@ -0,0 +1,52 @@
+/// Extracts the lowercase host from a game server url, so that
+/// `https://S1.SFGame.net/` and `s1.sfgame.net` are the same server
+pub fn server_host(url: &str) -> String {
+    let url = url.trim();
+    let url = ["https://", "http://"]
+        .into_iter()
+        .find_map(|scheme| {
+            url.get(..scheme.len())
+                .filter(|a| a.eq_ignore_ascii_case(scheme))
+                .map(|_| &url[scheme.len()..])
+        })
+        .unwrap_or(url);
+    let host = url.split(['/', ':', '?', '#']).next().unwrap_or_default();
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Checks if `host` is `domain`, or any subdomain of it. Both have to be
+/// normalized already
+pub fn is_on_domain(host: &str, domain: &str) -> bool {
+    host.strip_suffix(domain)
+        .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts() {
+        for (url, host) in [
+            ("s1.sfgame.net", "s1.sfgame.net"),
+            ("https://s1.sfgame.net/", "s1.sfgame.net"),
+            ("HTTP://S1.SFGame.NET", "s1.sfgame.net"),
+            ("s1.sfgame.net:443/cmd.php?req=1", "s1.sfgame.net"),
+            ("  s1.sfgame.net.  ", "s1.sfgame.net"),
+            ("", ""),
+        ] {
+            assert_eq!(server_host(url), host, "{url}");
+        }
+    }
+
+    #[test]
+    fn domains() {
+        assert!(is_on_domain("sfgame.net", "sfgame.net"));
+        assert!(is_on_domain("s1.sfgame.net", "sfgame.net"));
+        assert!(is_on_domain("a.b.sfgame.net", "sfgame.net"));
+        assert!(!is_on_domain("notsfgame.net", "sfgame.net"));
+        assert!(!is_on_domain("sfgame.net.evil.com", "sfgame.net"));
+        assert!(!is_on_domain("sfgame.eu", "sfgame.net"));
+        assert!(!is_on_domain("", "sfgame.net"));
+    }
+}